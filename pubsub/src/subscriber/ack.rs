@@ -0,0 +1,380 @@
+//! Exactly-once ack/modack support.
+//!
+//! When a subscription has [exactly-once delivery](https://cloud.google.com/pubsub/docs/exactly-once-delivery)
+//! enabled, `Acknowledge` and `ModifyAckDeadline` responses carry a per-ack-id
+//! error map describing whether each ack id was durably processed. This
+//! module turns that error map into an [`AckResultFuture`] per message, so
+//! callers can await the real outcome of an ack/modack instead of treating it
+//! as fire-and-forget, retrying transient failures with backoff until
+//! [`LeaseExtensionSetting::EXACTLY_ONCE_DELIVERY_RETRY_DEADLINE`] elapses.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use prost::Message as _;
+use tonic::transport::Channel;
+
+use tokio::{sync::oneshot, time::Instant};
+
+use google_cloud_googleapis::{
+    pubsub::v1::{subscriber_client::SubscriberClient, AcknowledgeRequest, ModifyAckDeadlineRequest},
+    rpc::{ErrorInfo, Status as RpcStatus},
+};
+
+use super::lease::LeaseExtensionSetting;
+
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The outcome of a single ack id within an exactly-once `Acknowledge` or
+/// `ModifyAckDeadline` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AckResponse {
+    /// The server durably recorded the ack/modack.
+    Success,
+    /// The server reported a permanent, non-transient failure for the ack
+    /// id other than the ack id being invalid. Retrying will never help.
+    PermanentFailure(AckErrorReason),
+    /// The server failed to process the request, but the ack id itself is
+    /// still valid and the request may be retried.
+    TransientFailure(AckErrorReason),
+    /// The server reported the ack id as invalid
+    /// (`PERMANENT_FAILURE_INVALID_ACK_ID`), e.g. the message was already
+    /// acked or its ack deadline has expired. Retrying will never help.
+    InvalidAckId,
+}
+
+/// The server-provided error code backing a [`AckResponse::PermanentFailure`]
+/// or [`AckResponse::TransientFailure`], kept around for logging/debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AckErrorReason(pub String);
+
+impl AckResponse {
+    /// Classifies the error code the server reported for one ack id, or
+    /// `None` if the ack id is absent from the error map (meaning success).
+    fn classify(error_code: Option<&str>) -> AckResponse {
+        match error_code {
+            None => AckResponse::Success,
+            Some("PERMANENT_FAILURE_INVALID_ACK_ID") => AckResponse::InvalidAckId,
+            Some(code) if code.starts_with("TRANSIENT_") => {
+                AckResponse::TransientFailure(AckErrorReason(code.to_string()))
+            }
+            Some(code) => AckResponse::PermanentFailure(AckErrorReason(code.to_string())),
+        }
+    }
+}
+
+type BoxAckFuture = Pin<Box<dyn Future<Output = HashMap<String, String>> + Send>>;
+
+/// Abstraction over the Subscriber `Acknowledge`/`ModifyAckDeadline` RPCs,
+/// narrowed to what the retry loop needs: send a batch, get back the
+/// per-ack-id error codes the server reported.
+pub(crate) trait ExactlyOnceSender: Send + Sync + 'static {
+    fn send_ack(&self, ack_ids: Vec<String>) -> BoxAckFuture;
+    fn send_modack(&self, ack_ids: Vec<String>, ack_deadline: Duration) -> BoxAckFuture;
+}
+
+/// The real [`ExactlyOnceSender`]: sends ack/modack requests over the
+/// Subscriber gRPC client for `subscription`.
+pub(crate) struct SubscriberAckSender {
+    client: SubscriberClient<Channel>,
+    subscription: String,
+}
+
+impl SubscriberAckSender {
+    pub(crate) fn new(client: SubscriberClient<Channel>, subscription: String) -> Self {
+        Self { client, subscription }
+    }
+}
+
+impl ExactlyOnceSender for SubscriberAckSender {
+    fn send_ack(&self, ack_ids: Vec<String>) -> BoxAckFuture {
+        let mut client = self.client.clone();
+        let subscription = self.subscription.clone();
+        Box::pin(async move {
+            let request = AcknowledgeRequest {
+                subscription,
+                ack_ids: ack_ids.clone(),
+            };
+            match client.acknowledge(request).await {
+                Ok(_) => HashMap::new(),
+                Err(status) => exactly_once_errors_from_status(&status, &ack_ids),
+            }
+        })
+    }
+
+    fn send_modack(&self, ack_ids: Vec<String>, ack_deadline: Duration) -> BoxAckFuture {
+        let mut client = self.client.clone();
+        let subscription = self.subscription.clone();
+        Box::pin(async move {
+            let request = ModifyAckDeadlineRequest {
+                subscription,
+                ack_ids: ack_ids.clone(),
+                ack_deadline_seconds: ack_deadline.as_secs() as i32,
+            };
+            match client.modify_ack_deadline(request).await {
+                Ok(_) => HashMap::new(),
+                Err(status) => exactly_once_errors_from_status(&status, &ack_ids),
+            }
+        })
+    }
+}
+
+/// For exactly-once subscriptions, a per-ack-id failure is reported as a
+/// `google.rpc.ErrorInfo` detail on the RPC `Status`, whose `metadata` maps
+/// each failed ack id to its error code. When that map calls out at least
+/// one of the requested ack ids, the ones it doesn't call out succeeded. If
+/// it calls out none of them, including when the status carries no such
+/// detail at all (e.g. the whole RPC failed before the server could even
+/// look at ack ids), every requested ack id is treated as transiently failed
+/// so it's retried rather than silently dropped.
+fn exactly_once_errors_from_status(status: &tonic::Status, ack_ids: &[String]) -> HashMap<String, String> {
+    let mut errors = HashMap::new();
+
+    if let Ok(rpc_status) = RpcStatus::decode(status.details()) {
+        for detail in &rpc_status.details {
+            if !detail.type_url.ends_with("google.rpc.ErrorInfo") {
+                continue;
+            }
+            if let Ok(info) = ErrorInfo::decode(detail.value.as_slice()) {
+                for ack_id in ack_ids {
+                    if let Some(code) = info.metadata.get(ack_id) {
+                        errors.insert(ack_id.clone(), code.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        for ack_id in ack_ids {
+            errors.insert(ack_id.clone(), "TRANSIENT_FAILURE_UNKNOWN".to_string());
+        }
+    }
+
+    errors
+}
+
+/// Drives the exactly-once ack/modack retry loop for a subscription.
+pub(crate) struct AckRetrier {
+    sender: Arc<dyn ExactlyOnceSender>,
+}
+
+impl AckRetrier {
+    pub(crate) fn new(sender: Arc<dyn ExactlyOnceSender>) -> Self {
+        Self { sender }
+    }
+
+    /// Acks `ack_id`, returning a future that resolves once the server has
+    /// durably recorded it, or acking has failed permanently, or
+    /// transient failures have persisted past the retry deadline.
+    pub(crate) fn ack(&self, ack_id: String) -> AckResultFuture {
+        self.retry(ack_id, None)
+    }
+
+    /// Modacks `ack_id` to `ack_deadline`, with the same retry semantics as
+    /// [`Self::ack`].
+    pub(crate) fn modack(&self, ack_id: String, ack_deadline: Duration) -> AckResultFuture {
+        self.retry(ack_id, Some(ack_deadline))
+    }
+
+    /// Nacks `ack_id`, returning a future with the same retry semantics as
+    /// [`Self::ack`].
+    ///
+    /// A nack is a modack to a zero deadline, so the message becomes
+    /// immediately eligible for redelivery.
+    pub(crate) fn nack(&self, ack_id: String) -> AckResultFuture {
+        self.modack(ack_id, Duration::ZERO)
+    }
+
+    fn retry(&self, ack_id: String, ack_deadline: Option<Duration>) -> AckResultFuture {
+        let (tx, rx) = oneshot::channel();
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let response = retry_until_resolved(sender.as_ref(), &ack_id, ack_deadline).await;
+            // Nothing to do if the caller dropped the future; it already
+            // gave up on the result.
+            let _ = tx.send(response);
+        });
+        AckResultFuture { rx }
+    }
+}
+
+async fn retry_until_resolved(
+    sender: &dyn ExactlyOnceSender,
+    ack_id: &str,
+    ack_deadline: Option<Duration>,
+) -> AckResponse {
+    let retry_deadline = Instant::now() + LeaseExtensionSetting::EXACTLY_ONCE_DELIVERY_RETRY_DEADLINE;
+    let mut backoff = INITIAL_RETRY_BACKOFF;
+
+    loop {
+        let errors = match ack_deadline {
+            Some(deadline) => sender.send_modack(vec![ack_id.to_string()], deadline).await,
+            None => sender.send_ack(vec![ack_id.to_string()]).await,
+        };
+
+        let response = AckResponse::classify(errors.get(ack_id).map(String::as_str));
+
+        if !matches!(response, AckResponse::TransientFailure(_)) || Instant::now() >= retry_deadline {
+            return response;
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+    }
+}
+
+/// A future resolving to the [`AckResponse`] for one ack/modack request on
+/// an exactly-once-enabled subscription.
+pub(crate) struct AckResultFuture {
+    rx: oneshot::Receiver<AckResponse>,
+}
+
+impl Future for AckResultFuture {
+    type Output = AckResponse;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(response)) => Poll::Ready(response),
+            // The retrying task panicked; surface it as a transient failure
+            // so callers applying "retry on transient" logic of their own
+            // still behave sensibly.
+            Poll::Ready(Err(_)) => Poll::Ready(AckResponse::TransientFailure(AckErrorReason(
+                "ack retry task terminated unexpectedly".to_string(),
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn classify_maps_error_codes() {
+        assert_eq!(AckResponse::classify(None), AckResponse::Success);
+        assert_eq!(
+            AckResponse::classify(Some("PERMANENT_FAILURE_INVALID_ACK_ID")),
+            AckResponse::InvalidAckId
+        );
+        assert_eq!(
+            AckResponse::classify(Some("TRANSIENT_FAILURE_OTHER")),
+            AckResponse::TransientFailure(AckErrorReason("TRANSIENT_FAILURE_OTHER".to_string()))
+        );
+        assert_eq!(
+            AckResponse::classify(Some("PERMANENT_FAILURE_OTHER")),
+            AckResponse::PermanentFailure(AckErrorReason("PERMANENT_FAILURE_OTHER".to_string()))
+        );
+    }
+
+    /// A fake [`ExactlyOnceSender`] that returns the `n`th response from
+    /// `script` (repeating the last one once exhausted) for every call,
+    /// regardless of which ack id or RPC kind was requested.
+    struct ScriptedSender {
+        script: Vec<Option<&'static str>>,
+        calls: AtomicUsize,
+    }
+
+    impl ScriptedSender {
+        fn new(script: Vec<Option<&'static str>>) -> Self {
+            Self {
+                script,
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        fn call_count(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn respond(&self, ack_ids: Vec<String>) -> BoxAckFuture {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let index = call.min(self.script.len() - 1);
+            let mut errors = HashMap::new();
+            if let Some(code) = self.script[index] {
+                errors.insert(ack_ids[0].clone(), code.to_string());
+            }
+            Box::pin(async move { errors })
+        }
+    }
+
+    impl ExactlyOnceSender for ScriptedSender {
+        fn send_ack(&self, ack_ids: Vec<String>) -> BoxAckFuture {
+            self.respond(ack_ids)
+        }
+
+        fn send_modack(&self, ack_ids: Vec<String>, _ack_deadline: Duration) -> BoxAckFuture {
+            self.respond(ack_ids)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_failure_then_succeeds() {
+        let sender = ScriptedSender::new(vec![Some("TRANSIENT_FAILURE_UNAVAILABLE"), None]);
+
+        let response = retry_until_resolved(&sender, "ack-1", None).await;
+
+        assert_eq!(response, AckResponse::Success);
+        assert_eq!(sender.call_count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_failure_is_not_retried() {
+        let sender = ScriptedSender::new(vec![Some("PERMANENT_FAILURE_OTHER")]);
+
+        let response = retry_until_resolved(&sender, "ack-1", None).await;
+
+        assert_eq!(
+            response,
+            AckResponse::PermanentFailure(AckErrorReason("PERMANENT_FAILURE_OTHER".to_string()))
+        );
+        assert_eq!(sender.call_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invalid_ack_id_is_not_retried() {
+        let sender = ScriptedSender::new(vec![Some("PERMANENT_FAILURE_INVALID_ACK_ID")]);
+
+        let response = retry_until_resolved(&sender, "ack-1", None).await;
+
+        assert_eq!(response, AckResponse::InvalidAckId);
+        assert_eq!(sender.call_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_once_the_retry_deadline_elapses() {
+        let sender = ScriptedSender::new(vec![Some("TRANSIENT_FAILURE_UNAVAILABLE")]);
+
+        let response = retry_until_resolved(&sender, "ack-1", None).await;
+
+        assert_eq!(
+            response,
+            AckResponse::TransientFailure(AckErrorReason("TRANSIENT_FAILURE_UNAVAILABLE".to_string()))
+        );
+        // Backing off from 100ms, doubling and capped at 60s, takes on the
+        // order of 20 calls to exceed the 600s retry deadline -- nowhere
+        // near an unbounded retry loop.
+        assert!(sender.call_count() < 25, "got {} calls", sender.call_count());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ack_retrier_resolves_through_the_public_api() {
+        let sender = Arc::new(ScriptedSender::new(vec![None]));
+        let retrier = AckRetrier::new(sender);
+
+        let response = retrier.ack("ack-1".to_string()).await;
+
+        assert_eq!(response, AckResponse::Success);
+    }
+}
+
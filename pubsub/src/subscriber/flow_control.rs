@@ -0,0 +1,195 @@
+//! Client-side flow control for streaming pull.
+//!
+//! Bounds the number and total byte size of outstanding (received but not
+//! yet acked or nacked) messages, so the streaming pull loop backpressures
+//! instead of buffering delivered messages unboundedly while the caller's
+//! message handler falls behind. Capacity is released back as messages are
+//! settled, i.e. on [`DeadlinesTracker::done`](super::lease::DeadlinesTracker::done).
+
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Configures [`FlowController`] limits on outstanding messages.
+#[derive(Debug, Clone)]
+pub struct FlowControlSetting {
+    /// Maximum number of unacked messages outstanding at once. Must be > 0.
+    pub max_outstanding_messages: usize,
+    /// Maximum total size, in bytes, of unacked messages outstanding at
+    /// once. Must be > 0.
+    pub max_outstanding_bytes: usize,
+    /// When `true`, the limits above are enforced purely client-side, by
+    /// pausing reads off the stream, instead of being sent to the server as
+    /// stream flow-control tokens. This is an escape hatch for users who hit
+    /// server-side flow-control bugs and need the old client-only behavior
+    /// back; new subscribers should leave this `false`.
+    pub use_legacy_flow_control: bool,
+}
+
+impl Default for FlowControlSetting {
+    fn default() -> Self {
+        Self {
+            max_outstanding_messages: 1000,
+            max_outstanding_bytes: 1000 * 1000 * 1000, // 1G
+            use_legacy_flow_control: false,
+        }
+    }
+}
+
+/// Bounds the number and total byte size of outstanding messages.
+///
+/// `acquire` is called as messages arrive off the stream and resolves once
+/// both limits allow admitting the message; the returned [`FlowControlPermit`]
+/// releases that capacity back to the controller when dropped, which happens
+/// once the message is acked or nacked.
+pub(super) struct FlowController {
+    setting: FlowControlSetting,
+    messages: Arc<Semaphore>,
+    bytes: Arc<Semaphore>,
+}
+
+impl FlowController {
+    pub(super) fn new(setting: FlowControlSetting) -> Self {
+        let messages = Arc::new(Semaphore::new(setting.max_outstanding_messages));
+        let bytes = Arc::new(Semaphore::new(setting.max_outstanding_bytes));
+        Self {
+            setting,
+            messages,
+            bytes,
+        }
+    }
+
+    /// Reserves capacity for one message of `size` bytes, waiting until both
+    /// the message-count and byte-size limits allow it. A `size` larger than
+    /// `max_outstanding_bytes` is clamped so a single oversized message can't
+    /// deadlock the controller.
+    pub(super) async fn acquire(&self, size: usize) -> FlowControlPermit {
+        let size = size.min(self.setting.max_outstanding_bytes);
+
+        let messages_permit = self
+            .messages
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("flow control semaphore is never closed");
+        let bytes_permit = self.acquire_bytes(size).await;
+
+        FlowControlPermit {
+            _messages_permit: messages_permit,
+            _bytes_permit: bytes_permit,
+        }
+    }
+
+    /// Acquires `size` bytes worth of permits from the byte-size semaphore.
+    ///
+    /// `Semaphore::acquire_many_owned` takes a `u32` permit count, but
+    /// `max_outstanding_bytes` (and therefore `size`) is a `usize` with no
+    /// documented upper bound, so a single oversized request is acquired in
+    /// `u32::MAX`-sized chunks and merged into one permit rather than cast
+    /// down and silently truncated.
+    async fn acquire_bytes(&self, size: usize) -> OwnedSemaphorePermit {
+        let mut permit = self
+            .bytes
+            .clone()
+            .acquire_many_owned(0)
+            .await
+            .expect("flow control semaphore is never closed");
+
+        let mut remaining = size;
+        while remaining > 0 {
+            let chunk = remaining.min(u32::MAX as usize) as u32;
+            let chunk_permit = self
+                .bytes
+                .clone()
+                .acquire_many_owned(chunk)
+                .await
+                .expect("flow control semaphore is never closed");
+            permit.merge(chunk_permit);
+            remaining -= chunk as usize;
+        }
+
+        permit
+    }
+
+    /// Whether limits are enforced client-side only. When `true`, the
+    /// streaming pull loop must pause reads itself rather than relying on
+    /// the server to throttle delivery via stream flow-control tokens.
+    pub(super) fn is_legacy(&self) -> bool {
+        self.setting.use_legacy_flow_control
+    }
+}
+
+/// Held for as long as one message is outstanding. Dropping it, which
+/// happens once the message is acked or nacked, releases its reserved
+/// capacity back to the [`FlowController`] it came from.
+pub(super) struct FlowControlPermit {
+    _messages_permit: OwnedSemaphorePermit,
+    _bytes_permit: OwnedSemaphorePermit,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn acquire_spans_multiple_u32_max_chunks() {
+        let size = u32::MAX as usize + 5;
+        let controller = FlowController::new(FlowControlSetting {
+            max_outstanding_messages: 10,
+            max_outstanding_bytes: size + 10,
+            use_legacy_flow_control: false,
+        });
+
+        let permit = controller.acquire(size).await;
+
+        assert_eq!(controller.bytes.available_permits(), 10);
+        assert_eq!(controller.messages.available_permits(), 9);
+
+        drop(permit);
+
+        assert_eq!(controller.bytes.available_permits(), size + 10);
+        assert_eq!(controller.messages.available_permits(), 10);
+    }
+
+    #[tokio::test]
+    async fn acquire_clamps_oversized_messages_to_the_byte_limit() {
+        let controller = FlowController::new(FlowControlSetting {
+            max_outstanding_messages: 1,
+            max_outstanding_bytes: 100,
+            use_legacy_flow_control: false,
+        });
+
+        // A single message larger than the configured limit must still be
+        // admittable -- clamped to the full limit -- instead of deadlocking.
+        let permit = controller.acquire(1_000_000).await;
+        assert_eq!(controller.bytes.available_permits(), 0);
+
+        drop(permit);
+        assert_eq!(controller.bytes.available_permits(), 100);
+    }
+
+    #[tokio::test]
+    async fn acquire_blocks_until_a_permit_is_released() {
+        let controller = FlowController::new(FlowControlSetting {
+            max_outstanding_messages: 1,
+            max_outstanding_bytes: 1000,
+            use_legacy_flow_control: false,
+        });
+
+        let first = controller.acquire(10).await;
+
+        let mut second = Box::pin(controller.acquire(10));
+        tokio::select! {
+            _ = &mut second => panic!("acquire resolved while the only message permit was held"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+        }
+
+        drop(first);
+
+        tokio::time::timeout(Duration::from_millis(200), second)
+            .await
+            .expect("acquire should resolve once the held permit was released");
+    }
+}
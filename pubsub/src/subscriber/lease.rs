@@ -9,6 +9,53 @@ use std::{
     time::{Duration, Instant},
 };
 
+use google_cloud_googleapis::pubsub::v1::RetryPolicy as InternalRetryPolicy;
+
+/// Configures the exponential-backoff redelivery of messages that are
+/// nacked or whose ack deadline expires, set on subscription creation or
+/// update.
+///
+/// This complements [`LeaseExtensionSetting`]: `LeaseExtensionSetting`
+/// governs how long we hold on to a message before it becomes eligible for
+/// redelivery, while `RetryPolicy` governs how the server spaces out
+/// redeliveries once that happens, instead of redelivering immediately.
+///
+/// See <https://cloud.google.com/pubsub/docs/handling-failures#retry_policy>.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The minimum delay between consecutive deliveries of a given message.
+    /// Must be between 0 and 600 seconds (inclusive).
+    pub minimum_backoff: Duration,
+    /// The maximum delay between consecutive deliveries of a given message.
+    /// Must be between 0 and 600 seconds (inclusive).
+    pub maximum_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            minimum_backoff: Duration::from_secs(10),
+            maximum_backoff: Duration::from_secs(600),
+        }
+    }
+}
+
+impl From<RetryPolicy> for InternalRetryPolicy {
+    fn from(policy: RetryPolicy) -> Self {
+        InternalRetryPolicy {
+            minimum_backoff: Some(duration_to_prost(policy.minimum_backoff)),
+            maximum_backoff: Some(duration_to_prost(policy.maximum_backoff)),
+        }
+    }
+}
+
+fn duration_to_prost(duration: Duration) -> prost_types::Duration {
+    prost_types::Duration {
+        seconds: duration.as_secs() as i64,
+        nanos: duration.subsec_nanos() as i32,
+    }
+}
+
 /// Configures the automatic extension of the acknowledgement deadline.
 ///
 /// See <https://cloud.google.com/pubsub/docs/lease-management>.
@@ -48,10 +95,16 @@ impl LeaseExtensionSetting {
     const MAX_DURATION_PER_LEASE_EXTENSION: Duration = Duration::from_secs(10 * 60);
     const MIN_DURATION_PER_LEASE_EXTENSION: Duration = Duration::from_secs(10);
     const MIN_DURATION_PER_LEASE_EXTENSION_EXACTLY_ONCE: Duration = Duration::from_secs(60);
-    const EXACTLY_ONCE_DELIVERY_RETRY_DEADLINE: Duration = Duration::from_secs(600);
+    pub(super) const EXACTLY_ONCE_DELIVERY_RETRY_DEADLINE: Duration = Duration::from_secs(600);
 
-    /// TODO(gwik)
+    /// Clamps `ack_deadline` between the configured (or default) min and max
+    /// extension periods. `ack_deadline` is normally the p99 of observed ack
+    /// latency, as tracked by [`AckLatencyDistribution`]; it is also never
+    /// allowed to exceed [`Self::MAX_DURATION_PER_LEASE_EXTENSION`], so a
+    /// handful of slow outliers can't blow out every subsequent extension.
     fn bounded_duration(&self, ack_deadline: Duration, exactly_once: bool) -> Duration {
+        let ack_deadline = ack_deadline.min(Self::MAX_DURATION_PER_LEASE_EXTENSION);
+
         // Respect the `max_extension_period`.
         let ack_deadline = if let Some(max_extension) = self.max_extension_period {
             ack_deadline.min(max_extension)
@@ -100,16 +153,103 @@ pub(super) struct Deadline {
     // token: CancellactionToken
 }
 
+/// Number of exponentially-spaced buckets kept by [`AckLatencyDistribution`].
+///
+/// `floor(log(600) / log(BUCKET_BASE))` is ~24, so this comfortably covers
+/// the full 1s..600s range that `modifyAckDeadline` cares about. Outliers
+/// beyond 600s land in the buckets above that, and the last bucket acts as
+/// an overflow bucket for everything from `1.3^31`s (~3400s) up.
+const LATENCY_DISTRIBUTION_NUM_BUCKETS: usize = 32;
+const LATENCY_DISTRIBUTION_BUCKET_BASE: f64 = 1.3;
+
+/// Minimum number of recorded ack latencies before the p99 is trusted to
+/// drive the next lease extension. Below this, we fall back to the
+/// min-extension clamp in [`LeaseExtensionSetting::bounded_duration`].
+const MIN_DATAPOINTS_FOR_PERCENTILE: u64 = 10;
+
+/// A histogram of ack latencies (time from `register` to `done`), used to
+/// derive the 99th percentile that drives the next lease extension period.
+///
+/// Buckets are exponentially spaced (base 1.3s) rather than fixed-width so a
+/// small, constant-size histogram can cover the full range of plausible ack
+/// latencies, from just over a second to several minutes.
+#[derive(Debug, Clone)]
+struct AckLatencyDistribution {
+    buckets: [u64; LATENCY_DISTRIBUTION_NUM_BUCKETS],
+    count: u64,
+}
+
+impl AckLatencyDistribution {
+    fn new() -> Self {
+        Self {
+            buckets: [0; LATENCY_DISTRIBUTION_NUM_BUCKETS],
+            count: 0,
+        }
+    }
+
+    fn bucket_for(latency: Duration) -> usize {
+        // Latencies below 1s all fall in bucket 0: log(x) is negative there,
+        // and we don't need sub-second resolution.
+        let latency_secs = latency.as_secs_f64().max(1.0);
+        let bucket = (latency_secs.ln() / LATENCY_DISTRIBUTION_BUCKET_BASE.ln()).floor();
+        (bucket as usize).min(LATENCY_DISTRIBUTION_NUM_BUCKETS - 1)
+    }
+
+    fn record(&mut self, latency: Duration) {
+        self.buckets[Self::bucket_for(latency)] += 1;
+        self.count += 1;
+    }
+
+    /// Returns the smallest duration `d` such that at least a `p` fraction of
+    /// recorded latencies are `<= d`, or `None` if fewer than
+    /// [`MIN_DATAPOINTS_FOR_PERCENTILE`] latencies have been recorded yet.
+    ///
+    /// The returned duration is the upper boundary of the bucket containing
+    /// the percentile, not an interpolated value, since only bucket counts
+    /// are retained.
+    fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count < MIN_DATAPOINTS_FOR_PERCENTILE {
+            return None;
+        }
+
+        let target = (self.count as f64 * p).ceil() as u64;
+        let mut cumulative = 0u64;
+        for (i, &bucket_count) in self.buckets.iter().enumerate() {
+            cumulative += bucket_count;
+            if cumulative >= target {
+                let upper_bound_secs = LATENCY_DISTRIBUTION_BUCKET_BASE.powi(i as i32 + 1);
+                return Some(Duration::from_secs_f64(upper_bound_secs));
+            }
+        }
+
+        // Unreachable: `cumulative` equals `self.count` after the last
+        // bucket, and `target <= self.count` always holds for `p <= 1.0`, so
+        // the loop above always returns before falling through here.
+        unreachable!("target percentile must fall within some bucket")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(super) struct DeadlinesTracker {
     setting: LeaseExtensionSetting,
     exactly_once: bool,
     deadlines: HashMap<String, Deadline>,
+    ack_latency_distribution: AckLatencyDistribution,
 }
 
 impl DeadlinesTracker {
+    /// The percentile of ack latency used to compute the next
+    /// `modifyAckDeadline` extension period, per the
+    /// [`LeaseExtensionSetting::min_extension_period`] docs.
+    const ACK_DEADLINE_PERCENTILE: f64 = 0.99;
+
     pub(super) fn new(setting: LeaseExtensionSetting, exactly_once: bool) -> Self {
-        Self { setting, exactly_once }
+        Self {
+            setting,
+            exactly_once,
+            deadlines: HashMap::new(),
+            ack_latency_distribution: AckLatencyDistribution::new(),
+        }
     }
 
     pub(super) fn register(&mut self, ack_id: String) -> Deadline {
@@ -121,6 +261,70 @@ impl DeadlinesTracker {
     }
 
     pub(super) fn done(&mut self, ack_id: &String) -> Option<Deadline> {
-        self.deadlines.remove(ack_id)
+        let deadline = self.deadlines.remove(ack_id)?;
+        self.ack_latency_distribution.record(deadline.start.elapsed());
+        Some(deadline)
+    }
+
+    /// The duration to request for the next `modifyAckDeadline` call, driven
+    /// by the p99 of observed ack latency once enough datapoints have been
+    /// collected, and bounded by `setting`.
+    pub(super) fn next_ack_deadline(&self) -> Duration {
+        let observed_latency = self
+            .ack_latency_distribution
+            .percentile(Self::ACK_DEADLINE_PERCENTILE)
+            .unwrap_or_default();
+
+        self.setting.bounded_duration(observed_latency, self.exactly_once)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_none_below_min_datapoints() {
+        let mut dist = AckLatencyDistribution::new();
+        for _ in 0..(MIN_DATAPOINTS_FOR_PERCENTILE - 1) {
+            dist.record(Duration::from_secs(5));
+        }
+        assert_eq!(dist.percentile(0.99), None);
+    }
+
+    #[test]
+    fn percentile_some_once_min_datapoints_reached() {
+        let mut dist = AckLatencyDistribution::new();
+        for _ in 0..MIN_DATAPOINTS_FOR_PERCENTILE {
+            dist.record(Duration::from_secs(5));
+        }
+        let p99 = dist.percentile(0.99).expect("enough datapoints recorded");
+        // All recorded latencies are 5s, so the bucket boundary the
+        // percentile resolves to must be at least that.
+        assert!(p99 >= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn percentile_is_driven_by_tail_latency() {
+        let mut dist = AckLatencyDistribution::new();
+        for _ in 0..(MIN_DATAPOINTS_FOR_PERCENTILE - 1) {
+            dist.record(Duration::from_secs(2));
+        }
+        dist.record(Duration::from_secs(300));
+
+        let p99 = dist.percentile(0.99).expect("enough datapoints recorded");
+        assert!(
+            p99 > Duration::from_secs(10),
+            "p99 should reflect the one tail latency, got {p99:?}"
+        );
+    }
+
+    #[test]
+    fn next_ack_deadline_falls_back_to_min_before_enough_datapoints() {
+        let tracker = DeadlinesTracker::new(LeaseExtensionSetting::default(), false);
+        assert_eq!(
+            tracker.next_ack_deadline(),
+            LeaseExtensionSetting::MIN_DURATION_PER_LEASE_EXTENSION
+        );
     }
 }
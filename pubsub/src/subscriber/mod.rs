@@ -0,0 +1,107 @@
+//! Streaming pull message handling: lease tracking, flow control, and (for
+//! exactly-once subscriptions) retried ack/modack.
+
+mod ack;
+mod flow_control;
+mod lease;
+
+use std::{collections::HashMap, sync::Arc};
+
+pub use ack::AckResponse;
+pub use flow_control::FlowControlSetting;
+pub use lease::{LeaseExtensionSetting, RetryPolicy};
+
+use ack::{AckResultFuture, AckRetrier, ExactlyOnceSender};
+use flow_control::{FlowControlPermit, FlowController};
+use lease::DeadlinesTracker;
+
+/// Drives one subscription's streaming pull session: tracks each delivered
+/// message's lease via [`DeadlinesTracker`] and its outstanding-capacity
+/// permit via [`FlowController`], and, for exactly-once subscriptions,
+/// resolves ack/nack/modack through the [`AckRetrier`] instead of firing
+/// them and forgetting.
+pub(crate) struct Subscriber {
+    deadlines: DeadlinesTracker,
+    flow_control: FlowController,
+    ack_retrier: Option<AckRetrier>,
+    outstanding_permits: HashMap<String, FlowControlPermit>,
+}
+
+impl Subscriber {
+    /// `ack_retrier` should be `Some` when, and only when, the subscription
+    /// has exactly-once delivery enabled; the streaming pull client is
+    /// responsible for building it around a [`ack::SubscriberAckSender`] for
+    /// the subscription's RPC client.
+    pub(crate) fn new(
+        lease_setting: LeaseExtensionSetting,
+        exactly_once: bool,
+        flow_control_setting: FlowControlSetting,
+        ack_retrier: Option<AckRetrier>,
+    ) -> Self {
+        debug_assert_eq!(
+            exactly_once,
+            ack_retrier.is_some(),
+            "ack_retrier must be set if and only if exactly-once delivery is enabled"
+        );
+        Self {
+            deadlines: DeadlinesTracker::new(lease_setting, exactly_once),
+            flow_control: FlowController::new(flow_control_setting),
+            ack_retrier,
+            outstanding_permits: HashMap::new(),
+        }
+    }
+
+    /// Called by the streaming pull loop as each message is received off the
+    /// stream, before it is handed to the caller's message handler. Starts
+    /// tracking its lease deadline, and reserves its flow-control capacity,
+    /// backpressuring the pull loop (by not resolving) until the message
+    /// and byte-size limits allow admitting a message of `size` bytes.
+    pub(crate) async fn on_received(&mut self, ack_id: String, size: usize) {
+        self.deadlines.register(ack_id.clone());
+        let permit = self.flow_control.acquire(size).await;
+        self.outstanding_permits.insert(ack_id, permit);
+    }
+
+    /// Acks `ack_id`. Stops tracking its lease and releases its
+    /// flow-control permit immediately, freeing that capacity for the
+    /// streaming pull loop to admit another message; for exactly-once
+    /// subscriptions, returns a future that resolves once the server
+    /// durably records the ack (see [`AckRetrier::ack`]). For best-effort
+    /// subscriptions, this only settles the message locally, sends no ack
+    /// RPC itself, and returns `None`.
+    pub(crate) fn ack(&mut self, ack_id: String) -> Option<AckResultFuture> {
+        self.settle(&ack_id);
+        self.ack_retrier.as_ref().map(|retrier| retrier.ack(ack_id))
+    }
+
+    /// Nacks `ack_id`, with the same settle/retry behavior as [`Self::ack`].
+    pub(crate) fn nack(&mut self, ack_id: String) -> Option<AckResultFuture> {
+        self.settle(&ack_id);
+        self.ack_retrier.as_ref().map(|retrier| retrier.nack(ack_id))
+    }
+
+    /// Extends `ack_id`'s deadline to [`DeadlinesTracker::next_ack_deadline`],
+    /// with the same exactly-once retry behavior as [`Self::ack`]. For
+    /// best-effort subscriptions, this sends nothing and returns `None`.
+    pub(crate) fn modack(&self, ack_id: String) -> Option<AckResultFuture> {
+        let deadline = self.deadlines.next_ack_deadline();
+        self.ack_retrier.as_ref().map(|retrier| retrier.modack(ack_id, deadline))
+    }
+
+    /// Marks `ack_id` as settled: stops lease tracking (feeding its latency
+    /// into the p99 distribution via [`DeadlinesTracker::done`]) and drops
+    /// its flow-control permit.
+    fn settle(&mut self, ack_id: &str) {
+        self.deadlines.done(&ack_id.to_string());
+        self.outstanding_permits.remove(ack_id);
+    }
+}
+
+/// Builds the [`AckRetrier`] for a subscription's streaming pull session
+/// when it has exactly-once delivery enabled, sending over `client`.
+pub(crate) fn exactly_once_ack_retrier(
+    client: google_cloud_googleapis::pubsub::v1::subscriber_client::SubscriberClient<tonic::transport::Channel>,
+    subscription: String,
+) -> AckRetrier {
+    AckRetrier::new(Arc::new(ack::SubscriberAckSender::new(client, subscription)) as Arc<dyn ExactlyOnceSender>)
+}
@@ -0,0 +1,69 @@
+//! Subscription creation and update, including the exponential-backoff
+//! redelivery policy applied to nacked or expired messages.
+
+use google_cloud_googleapis::pubsub::v1::{
+    subscriber_client::SubscriberClient, RetryPolicy as InternalRetryPolicy, Subscription as InternalSubscription,
+    UpdateSubscriptionRequest,
+};
+use prost_types::FieldMask;
+use tonic::transport::Channel;
+
+use crate::subscriber::RetryPolicy;
+
+/// Configuration applied when creating or updating a subscription.
+///
+/// This complements the per-client [`LeaseExtensionSetting`]: that setting
+/// governs how long a subscriber client holds on to a message before it
+/// becomes eligible for redelivery, while `retry_policy` governs how the
+/// server spaces out redeliveries once that happens.
+///
+/// [`LeaseExtensionSetting`]: crate::subscriber::LeaseExtensionSetting
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionConfig {
+    /// Exponential-backoff redelivery policy for nacked or expired messages.
+    /// Leaving this `None` redelivers immediately, as before.
+    pub retry_policy: Option<RetryPolicy>,
+}
+
+impl SubscriptionConfig {
+    fn into_internal_retry_policy(self) -> Option<InternalRetryPolicy> {
+        self.retry_policy.map(InternalRetryPolicy::from)
+    }
+}
+
+/// Creates `subscription` on `topic` with `config`.
+pub async fn create_subscription(
+    client: &mut SubscriberClient<Channel>,
+    subscription: String,
+    topic: String,
+    config: SubscriptionConfig,
+) -> Result<(), tonic::Status> {
+    let request = InternalSubscription {
+        name: subscription,
+        topic,
+        retry_policy: config.into_internal_retry_policy(),
+        ..Default::default()
+    };
+    client.create_subscription(request).await?;
+    Ok(())
+}
+
+/// Updates `subscription`'s retry policy to `config.retry_policy`.
+pub async fn update_subscription_retry_policy(
+    client: &mut SubscriberClient<Channel>,
+    subscription: String,
+    config: SubscriptionConfig,
+) -> Result<(), tonic::Status> {
+    let request = UpdateSubscriptionRequest {
+        subscription: Some(InternalSubscription {
+            name: subscription,
+            retry_policy: config.into_internal_retry_policy(),
+            ..Default::default()
+        }),
+        update_mask: Some(FieldMask {
+            paths: vec!["retry_policy".to_string()],
+        }),
+    };
+    client.update_subscription(request).await?;
+    Ok(())
+}
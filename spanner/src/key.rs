@@ -1,8 +1,10 @@
 use crate::statement::ToKind;
+use bigdecimal::BigDecimal;
 use google_cloud_googleapis::spanner::v1::key_range::{EndKeyType, StartKeyType};
 use google_cloud_googleapis::spanner::v1::KeyRange as InternalKeyRange;
 use google_cloud_googleapis::spanner::v1::KeySet as InternalKeySet;
 use prost_types::{value, ListValue, Value};
+use serde_json::Value as JsonValue;
 
 /// A Key can be either a Cloud Spanner row's primary key or a secondary index
 /// key. A Key can be used as:
@@ -33,11 +35,78 @@ use prost_types::{value, ListValue, Value};
 ///   - chrono::NaiveDateTime and Option<chrono::NaiveDateTime> are mapped to Cloud Spanner's TIMESTAMP type.
 ///   - chrono::DateTime and Option<chrono::DateTime> are mapped to Cloud Spanner's DATE type.
 ///   - google_cloud_spanner::value::CommitTimestamp and Option<google_cloud_spanner::value::CommitTimestamp> are mapped to Cloud Spanner's TIMESTAMP type.
+///   - bigdecimal::BigDecimal and Option<bigdecimal::BigDecimal> are mapped to Cloud Spanner's NUMERIC type.
+///   - serde_json::Value and Option<serde_json::Value> are mapped to Cloud Spanner's JSON type.
+///   - ArrayKind<T> where T: ToKind is mapped to Cloud Spanner's ARRAY type.
 #[derive(Clone)]
 pub struct Key {
     pub(crate) values: ListValue,
 }
 
+/// NUMERIC columns are sent to Cloud Spanner as the canonical decimal string
+/// form, the same encoding Cloud Spanner itself uses for NUMERIC values in
+/// query parameters and results.
+impl ToKind for BigDecimal {
+    fn to_kind(self) -> value::Kind {
+        value::Kind::StringValue(self.to_string())
+    }
+}
+
+impl ToKind for Option<BigDecimal> {
+    fn to_kind(self) -> value::Kind {
+        match self {
+            Some(v) => v.to_kind(),
+            None => value::Kind::NullValue(0),
+        }
+    }
+}
+
+/// JSON columns are sent to Cloud Spanner as the serialized JSON text; the
+/// backend stores and compares it as JSON, not as a plain string.
+impl ToKind for JsonValue {
+    fn to_kind(self) -> value::Kind {
+        value::Kind::StringValue(self.to_string())
+    }
+}
+
+impl ToKind for Option<JsonValue> {
+    fn to_kind(self) -> value::Kind {
+        match self {
+            Some(v) => v.to_kind(),
+            None => value::Kind::NullValue(0),
+        }
+    }
+}
+
+/// Wraps a `Vec<T>` so it can be used as an ARRAY key component.
+///
+/// `Vec<u8>` already has a dedicated `ToKind` impl mapping it to Cloud
+/// Spanner's BYTES type, so a blanket `impl<T: ToKind> ToKind for Vec<T>`
+/// would conflict with it. `ArrayKind` gives ARRAY columns their own type to
+/// key off of instead:
+/// ```
+/// let key = Key::one(ArrayKind(vec![1i64, 2, 3]));
+/// ```
+pub struct ArrayKind<T>(pub Vec<T>);
+
+/// ARRAY columns are sent as a nested `ListValue` of the element kind, so any
+/// `ArrayKind` wrapping a `Vec` of a `ToKind` type, including another
+/// `ArrayKind`, can be used as an ARRAY key component.
+impl<T> ToKind for ArrayKind<T>
+where
+    T: ToKind,
+{
+    fn to_kind(self) -> value::Kind {
+        value::Kind::ListValue(ListValue {
+            values: self
+                .0
+                .into_iter()
+                .map(|v| Value { kind: Some(v.to_kind()) })
+                .collect(),
+        })
+    }
+}
+
 /// / A KeySet defines a collection of Cloud Spanner keys and/or key ranges. All
 /// / the keys are expected to be in the same table or index. The keys need not be
 /// / sorted in any particular way.
@@ -184,6 +253,16 @@ impl KeyRange {
     pub fn new(start: Key, end: Key, kind: RangeKind) -> KeyRange {
         KeyRange { start, end, kind }
     }
+
+    /// Whether the start key itself is included in the range.
+    fn includes_start(&self) -> bool {
+        matches!(self.kind, RangeKind::ClosedClosed | RangeKind::ClosedOpen)
+    }
+
+    /// Whether the end key itself is included in the range.
+    fn includes_end(&self) -> bool {
+        matches!(self.kind, RangeKind::ClosedClosed | RangeKind::OpenClosed)
+    }
 }
 
 impl From<KeyRange> for InternalKeyRange {
@@ -282,3 +361,227 @@ impl From<Vec<Key>> for KeySet {
         }
     }
 }
+
+impl KeySet {
+    /// Returns a [`KeySetBuilder`] for combining an arbitrary mix of `Key`s
+    /// and `KeyRange`s into a single `KeySet`.
+    pub fn builder() -> KeySetBuilder {
+        KeySetBuilder::default()
+    }
+
+    /// Returns a new `KeySet` containing the keys and ranges of both `self`
+    /// and `other`.
+    pub fn union(mut self, other: KeySet) -> KeySet {
+        self.extend(other);
+        self
+    }
+
+    /// Merges `other`'s keys and ranges into `self` in place.
+    pub fn extend(&mut self, other: KeySet) {
+        self.inner.all = self.inner.all || other.inner.all;
+        self.inner.keys.extend(other.inner.keys);
+        self.inner.ranges.extend(other.inner.ranges);
+    }
+}
+
+/// Accumulates a mix of [`Key`]s and [`KeyRange`]s into a single [`KeySet`].
+///
+/// The [`KeySet`] docs describe a "KeySets function to create a KeySet
+/// consisting of multiple Keys and KeyRanges"; this is that entry point,
+/// since the `From` impls above only ever build a `KeySet` from keys *or*
+/// ranges.
+///
+/// ```
+/// let set = KeySet::builder()
+///     .add_key(Key::one("alice"))
+///     .add_range(KeyRange::new(Key::one("m"), Key::one("z"), RangeKind::ClosedOpen))
+///     .build();
+/// ```
+#[derive(Clone, Default)]
+pub struct KeySetBuilder {
+    keys: Vec<Key>,
+    ranges: Vec<KeyRange>,
+}
+
+impl KeySetBuilder {
+    /// Adds a single point key to the set.
+    pub fn add_key(mut self, key: Key) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Adds a key range to the set.
+    pub fn add_range(mut self, range: KeyRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Builds the `KeySet`.
+    ///
+    /// Point keys that exactly match the included start or end boundary of
+    /// an added range are dropped, since the Cloud Spanner backend already
+    /// treats a key specified both ways as specified once; this avoids
+    /// sending that redundant key material over the wire.
+    ///
+    /// This is the only overlap `build` detects: a key that falls strictly
+    /// inside a range, rather than exactly on one of its included
+    /// boundaries, is kept and sent alongside the range as-is. Cloud Spanner
+    /// tolerates that redundancy, so `build` never drops more than it can
+    /// prove is duplicate -- dropping a key that turned out not to overlap
+    /// would silently narrow the `KeySet`. Detecting interior overlap would
+    /// additionally require each key column's declared sort order, which
+    /// `KeyRange` doesn't carry; callers who need interior keys excluded
+    /// must filter them out of `add_key`'s input themselves.
+    ///
+    /// TODO(gwik): only exact boundary matches are deduplicated here.
+    pub fn build(self) -> KeySet {
+        let boundary_values: Vec<&ListValue> = self
+            .ranges
+            .iter()
+            .flat_map(|range| {
+                let mut boundaries = Vec::with_capacity(2);
+                if range.includes_start() {
+                    boundaries.push(&range.start.values);
+                }
+                if range.includes_end() {
+                    boundaries.push(&range.end.values);
+                }
+                boundaries
+            })
+            .collect();
+
+        let keys = self
+            .keys
+            .into_iter()
+            .filter(|key| !boundary_values.iter().any(|boundary| **boundary == key.values))
+            .map(|key| key.values)
+            .collect();
+        let ranges = self.ranges.into_iter().map(InternalKeyRange::from).collect();
+
+        KeySet {
+            inner: InternalKeySet {
+                keys,
+                ranges,
+                all: false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_drops_point_key_on_closed_boundary() {
+        let range = KeyRange::new(Key::one(1i64), Key::one(10i64), RangeKind::ClosedClosed);
+        let set = KeySet::builder()
+            .add_key(Key::one(1i64))
+            .add_key(Key::one(5i64))
+            .add_range(range)
+            .build();
+
+        assert_eq!(set.inner.ranges.len(), 1);
+        // The point key equal to the range's closed start boundary is
+        // deduplicated away; the one strictly inside the range is kept,
+        // since detecting interior overlap isn't implemented.
+        assert_eq!(set.inner.keys.len(), 1);
+        assert_eq!(set.inner.keys[0], Key::one(5i64).values);
+    }
+
+    #[test]
+    fn build_keeps_point_key_on_excluded_open_boundary() {
+        let range = KeyRange::new(Key::one(1i64), Key::one(10i64), RangeKind::ClosedOpen);
+        let set = KeySet::builder()
+            .add_key(Key::one(10i64)) // the end boundary is excluded, so it's not a duplicate
+            .add_range(range)
+            .build();
+
+        assert_eq!(set.inner.keys.len(), 1);
+        assert_eq!(set.inner.keys[0], Key::one(10i64).values);
+    }
+
+    #[test]
+    fn build_with_no_ranges_keeps_all_keys() {
+        let set = KeySet::builder().add_key(Key::one("a")).add_key(Key::one("b")).build();
+
+        assert_eq!(set.inner.keys.len(), 2);
+        assert!(set.inner.ranges.is_empty());
+    }
+
+    #[test]
+    fn union_combines_keys_and_ranges() {
+        let a: KeySet = Key::one("a").into();
+        let b: KeySet = KeyRange::new(Key::one("m"), Key::one("z"), RangeKind::ClosedOpen).into();
+
+        let combined = a.union(b);
+        assert_eq!(combined.inner.keys.len(), 1);
+        assert_eq!(combined.inner.ranges.len(), 1);
+    }
+
+    #[test]
+    fn big_decimal_to_kind_is_canonical_decimal_string() {
+        let value: BigDecimal = "3.14".parse().unwrap();
+        assert_eq!(value.to_kind(), value::Kind::StringValue("3.14".to_string()));
+    }
+
+    #[test]
+    fn option_big_decimal_to_kind_maps_none_to_null() {
+        let value: Option<BigDecimal> = None;
+        assert_eq!(value.to_kind(), value::Kind::NullValue(0));
+
+        let value: Option<BigDecimal> = Some("3.14".parse().unwrap());
+        assert_eq!(value.to_kind(), value::Kind::StringValue("3.14".to_string()));
+    }
+
+    #[test]
+    fn json_value_to_kind_is_serialized_json_text() {
+        let value = serde_json::json!({"a": 1});
+        assert_eq!(value.to_kind(), value::Kind::StringValue(r#"{"a":1}"#.to_string()));
+    }
+
+    #[test]
+    fn option_json_value_to_kind_maps_none_to_null() {
+        let value: Option<JsonValue> = None;
+        assert_eq!(value.to_kind(), value::Kind::NullValue(0));
+
+        let value: Option<JsonValue> = Some(serde_json::json!(true));
+        assert_eq!(value.to_kind(), value::Kind::StringValue("true".to_string()));
+    }
+
+    #[test]
+    fn array_kind_to_kind_is_a_list_of_element_kinds() {
+        let value = ArrayKind(vec![1i64, 2, 3]);
+        assert_eq!(
+            value.to_kind(),
+            value::Kind::ListValue(ListValue {
+                values: vec![
+                    Value { kind: Some(1i64.to_kind()) },
+                    Value { kind: Some(2i64.to_kind()) },
+                    Value { kind: Some(3i64.to_kind()) },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn nested_array_kind_to_kind_produces_a_list_of_lists() {
+        let value = ArrayKind(vec![ArrayKind(vec![1i64, 2]), ArrayKind(vec![3i64])]);
+        let value::Kind::ListValue(outer) = value.to_kind() else {
+            panic!("expected a ListValue");
+        };
+        assert_eq!(outer.values.len(), 2);
+        assert_eq!(
+            outer.values[0].kind,
+            Some(value::Kind::ListValue(ListValue {
+                values: vec![Value { kind: Some(1i64.to_kind()) }, Value { kind: Some(2i64.to_kind()) }],
+            }))
+        );
+        assert_eq!(
+            outer.values[1].kind,
+            Some(value::Kind::ListValue(ListValue {
+                values: vec![Value { kind: Some(3i64.to_kind()) }],
+            }))
+        );
+    }
+}